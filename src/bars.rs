@@ -0,0 +1,238 @@
+use differential_dataflow::operators::*;
+use differential_dataflow::Collection;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use timely::dataflow::Scope;
+
+use crate::resolution::round_down_to;
+use crate::welford::WelfordVwap;
+use crate::{decimal_from_f64, FHLLVData, Trade};
+
+// Width of the pre-bucket `compute_bars` groups trades into before sweeping
+// for bar boundaries, so a single `reduce` group holds one day's trades for
+// one market rather than the whole multi-month series.
+const PRE_BUCKET_SECS: i64 = 24 * 60 * 60;
+
+// Which side of the trade volume bars are measured in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum By {
+    // Base-asset size (trade count until trades carry their own size).
+    Base,
+    // Quote-asset size (price * size).
+    Quote,
+}
+
+// A bar closes once its running total crosses `threshold`, measured either
+// by volume (in base or quote units) or by trade count.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum BarKind {
+    Volume { threshold: i64, by: By },
+    Tick { threshold: i64 },
+}
+
+// Builds volume/tick bars instead of fixed time buckets. Trades are grouped
+// by (day, market) rather than market alone — `reduce` still sees every
+// trade in the group at once, and some markets carry a large share of the
+// power-law-weighted trade volume, so bucketing by market alone would hold
+// that market's entire multi-month history in one `Vec` to sort. Capping the
+// group at a day bounds that to one day's trades per market, at the cost of
+// never building a bar that spans a day boundary (the in-progress bar at
+// each day's end is dropped, same as any bar that never crosses threshold).
+// Within a group we still sort the day's trades by timestamp ourselves
+// rather than relying on differential's key ordering, and sweep once,
+// closing a bar and starting the next whenever the running volume (or trade
+// count) crosses `threshold`.
+pub(crate) fn compute_bars<G: Scope<Timestamp = i64>>(
+    trades: &Collection<G, Trade, isize>,
+    kind: BarKind,
+) -> Collection<G, FHLLVData, isize> {
+    trades
+        .map(|trade| {
+            let day = round_down_to(trade.timestamp, PRE_BUCKET_SECS);
+            ((day, trade.market), (trade.timestamp, trade.price))
+        })
+        .reduce(move |(_, market), input, output| {
+            let mut ordered: Vec<(i64, Decimal)> = Vec::with_capacity(input.len());
+            for (val, count) in input.iter() {
+                let (ts, price) = **val;
+                for _ in 0..*count {
+                    ordered.push((ts, price));
+                }
+            }
+            ordered.sort_by_key(|(ts, _)| *ts);
+
+            let threshold = match kind {
+                BarKind::Volume { threshold, .. } => threshold as f64,
+                BarKind::Tick { threshold } => threshold as f64,
+            };
+
+            let mut bar = Bar::default();
+            let mut running = 0.0_f64;
+
+            for (ts, price) in ordered {
+                bar.push(ts, price);
+
+                running += match kind {
+                    BarKind::Volume { by: By::Base, .. } => 1.0,
+                    BarKind::Volume { by: By::Quote, .. } => price.to_f64().unwrap_or(0.0),
+                    BarKind::Tick { .. } => 1.0,
+                };
+
+                if running >= threshold {
+                    output.push((bar.finish(*market), 1));
+                    bar = Bar::default();
+                    running = 0.0;
+                }
+            }
+            // A partial bar that never crossed the threshold is dropped,
+            // matching trade_aggregation's behavior of only emitting closed bars.
+        })
+        .map(|(_, candle)| candle)
+}
+
+// Accumulates a single in-progress bar: OHLC, volume, and a Welford
+// mean/variance + VWAP pass over just this bar's trades.
+struct Bar {
+    open: Option<(i64, Decimal)>,
+    close: (i64, Decimal),
+    high: Decimal,
+    low: Decimal,
+    volume: i64,
+    stats: WelfordVwap,
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Bar {
+            open: None,
+            close: (i64::MIN, Decimal::ZERO),
+            high: Decimal::MIN,
+            low: Decimal::MAX,
+            volume: 0,
+            stats: WelfordVwap::new(),
+        }
+    }
+}
+
+impl Bar {
+    fn push(&mut self, ts: i64, price: Decimal) {
+        if self.open.is_none() {
+            self.open = Some((ts, price));
+        }
+        self.close = (ts, price);
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.volume += 1;
+        self.stats.push(price.to_f64().unwrap_or(0.0));
+    }
+
+    fn finish(self, market: u32) -> FHLLVData {
+        let open = self.open.unwrap_or(self.close);
+
+        FHLLVData {
+            timestamp: open.0,
+            market,
+            open: open.1,
+            high: self.high,
+            low: self.low,
+            close: self.close.1,
+            volume: self.volume,
+            mean: decimal_from_f64(self.stats.mean),
+            vwap: decimal_from_f64(self.stats.vwap()),
+            std_dev: decimal_from_f64(self.stats.std_dev()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use differential_dataflow::input::InputSession;
+
+    use super::*;
+
+    // Drives `compute_bars` over `trades` in a single-threaded dataflow and
+    // collects the bars it emits, in emission order.
+    fn run_bars(trades: Vec<Trade>, kind: BarKind) -> Vec<FHLLVData> {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let captured_inner = Arc::clone(&captured);
+
+        timely::execute_directly(move |worker| {
+            let mut input = InputSession::new();
+            let probe = worker.dataflow(|scope| {
+                let trades = input.to_collection(scope);
+                let mut probe = timely::dataflow::ProbeHandle::new();
+                compute_bars(&trades, kind)
+                    .inspect(move |(bar, _time, diff)| {
+                        if *diff > 0 {
+                            captured_inner.lock().unwrap().push(bar.clone());
+                        }
+                    })
+                    .probe_with(&mut probe);
+                probe
+            });
+
+            input.advance_to(0);
+            for trade in trades {
+                input.insert(trade);
+            }
+            input.advance_to(1);
+            input.flush();
+            worker.step_while(|| probe.less_than(input.time()));
+        });
+
+        Arc::try_unwrap(captured).unwrap().into_inner().unwrap()
+    }
+
+    fn trade(timestamp: i64, price: i64) -> Trade {
+        Trade {
+            timestamp,
+            market: 0,
+            price: Decimal::new(price, 0),
+        }
+    }
+
+    #[test]
+    fn tick_bars_close_on_trade_count() {
+        let trades = vec![
+            trade(0, 10),
+            trade(1, 20),
+            trade(2, 30), // closes the first 3-tick bar
+            trade(3, 40),
+            trade(4, 50), // only 2 trades left, bar never closes
+        ];
+
+        let mut bars = run_bars(trades, BarKind::Tick { threshold: 3 });
+        bars.sort_by_key(|bar| bar.timestamp);
+
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].volume, 3);
+        assert_eq!(bars[0].open, Decimal::new(10, 0));
+        assert_eq!(bars[0].close, Decimal::new(30, 0));
+    }
+
+    #[test]
+    fn quote_volume_bars_close_on_price_weighted_total() {
+        // Quote volume is price * size (size 1 per trade), so the running
+        // total is the sum of prices, not the trade count.
+        let trades = vec![
+            trade(0, 40),
+            trade(1, 40),
+            trade(2, 40), // running quote volume hits 120, closes the bar
+            trade(3, 1),
+        ];
+
+        let bars = run_bars(
+            trades,
+            BarKind::Volume {
+                threshold: 100,
+                by: By::Quote,
+            },
+        );
+
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].volume, 3);
+        assert_eq!(bars[0].high, Decimal::new(40, 0));
+    }
+}
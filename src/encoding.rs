@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use differential_dataflow::input::InputSession;
+use rust_decimal::Decimal;
+
+use crate::Trade;
+
+// Fixed-width binary layout for a `Trade`, little-endian, with explicit byte
+// offsets so records can be streamed (or mmapped) without a framing header:
+//
+//   0..8    timestamp: i64
+//   8..12   market: u32
+//   12..16  price mantissa lo: u32  \
+//   16..20  price mantissa mid: u32  } Decimal's 96-bit unsigned mantissa
+//   20..24  price mantissa hi: u32  /
+//   24      scale (low 7 bits) and sign (high bit)
+//   25..32  reserved, zeroed
+pub(crate) const TRADE_ENCODED_LEN: usize = 32;
+
+pub(crate) fn encode(trade: &Trade) -> [u8; TRADE_ENCODED_LEN] {
+    let mut buf = [0u8; TRADE_ENCODED_LEN];
+
+    buf[0..8].copy_from_slice(&trade.timestamp.to_le_bytes());
+    buf[8..12].copy_from_slice(&trade.market.to_le_bytes());
+
+    let unpacked = trade.price.unpack();
+    buf[12..16].copy_from_slice(&unpacked.lo.to_le_bytes());
+    buf[16..20].copy_from_slice(&unpacked.mid.to_le_bytes());
+    buf[20..24].copy_from_slice(&unpacked.hi.to_le_bytes());
+    buf[24] = unpacked.scale as u8 | if unpacked.negative { 0x80 } else { 0 };
+
+    buf
+}
+
+pub(crate) fn decode(buf: &[u8; TRADE_ENCODED_LEN]) -> Trade {
+    let timestamp = i64::from_le_bytes(buf[0..8].try_into().unwrap());
+    let market = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+
+    let lo = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+    let mid = u32::from_le_bytes(buf[16..20].try_into().unwrap());
+    let hi = u32::from_le_bytes(buf[20..24].try_into().unwrap());
+    let negative = buf[24] & 0x80 != 0;
+    let scale = (buf[24] & 0x7f) as u32;
+    let price = Decimal::from_parts(lo, mid, hi, negative, scale);
+
+    Trade {
+        timestamp,
+        market,
+        price,
+    }
+}
+
+// Streams fixed-width trade records straight from `path` into `input`,
+// replacing `generate_synthetic_trades` when driving the dataflow off a
+// captured trade dump instead of synthetic data.
+pub(crate) fn read_trades_into(
+    input: &mut InputSession<i64, Trade, isize>,
+    path: &str,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0u8; TRADE_ENCODED_LEN];
+
+    loop {
+        match reader.read_exact(&mut buf) {
+            Ok(()) => input.insert(decode(&buf)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    input.advance_to(1);
+    input.flush();
+    Ok(())
+}
+
+// Writes `trades` to `path` in the same fixed-width layout `read_trades_into`
+// streams back in, so a synthetic run can be captured once and replayed
+// later instead of regenerated every time.
+pub(crate) fn write_trades_to(trades: &[Trade], path: &str) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for trade in trades {
+        writer.write_all(&encode(trade))?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let trades = [
+            Trade {
+                timestamp: 1717192800,
+                market: 0,
+                price: Decimal::new(15, 1), // 1.5
+            },
+            Trade {
+                timestamp: 1717192860,
+                market: 699,
+                price: Decimal::ZERO,
+            },
+            Trade {
+                timestamp: -1,
+                market: 42,
+                price: Decimal::new(-123456789, 4), // -12345.6789
+            },
+            Trade {
+                timestamp: i64::MAX,
+                market: u32::MAX,
+                price: Decimal::new(9999999999, 5), // 99999.99999
+            },
+        ];
+
+        for trade in trades {
+            assert_eq!(decode(&encode(&trade)), trade);
+        }
+    }
+}
@@ -1,10 +1,18 @@
+mod bars;
+mod encoding;
+mod ohlcv_agg;
+mod resolution;
+mod welford;
+
 use differential_dataflow::input::InputSession;
-use differential_dataflow::operators::*;
-use differential_dataflow::Collection;
 use rand::distributions::{Distribution, Uniform};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use timely::dataflow::Scope;
+
+use bars::{compute_bars, BarKind, By};
+use encoding::{read_trades_into, write_trades_to};
+use ohlcv_agg::compute_candles_bounded;
+use resolution::{round_down_to, roll_up_candles, Resolution};
 
 // Represents a single trade
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -14,19 +22,38 @@ struct Trade {
     price: Decimal,
 }
 
-// Statistics of trades for a given hour
+// OHLCV candle for a given bucket and market, at whatever resolution it was
+// built at. `mean`/`std_dev` are a Welford online estimate of the trade
+// price distribution and `vwap` is the volume-weighted average price;
+// all three are accumulated in a single pass alongside open/high/low/close.
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 struct FHLLVData {
     timestamp: i64,
     market: u32,
+    open: Decimal,
     high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: i64,
+    mean: Decimal,
+    vwap: Decimal,
+    std_dev: Decimal,
 }
 
-fn round_down_to_hour(ts: i64) -> i64 {
-    (ts / 3600) * 3600
+// Converts an f64 statistic (mean/vwap/std_dev) into a `Decimal` so it can
+// sit alongside prices in `FHLLVData` without giving up `Ord`.
+fn decimal_from_f64(x: f64) -> Decimal {
+    Decimal::from_f64_retain(x).unwrap_or(Decimal::ZERO)
 }
 
-fn generate_synthetic_trades(input: &mut InputSession<i64, Trade, isize>) -> () {
+// Demo-only shortcut: collects all `NUM_TRADES` into one `Vec` and later
+// gets sorted whole by `drive_trades_event_time`, so this path still holds
+// the full synthetic run in memory at once. Bounded (unlike the per-key
+// blowup `compute_candles_bounded` fixes), but in tension with this crate's
+// point; a trade dump is instead streamed record-by-record via
+// `read_trades_into`, which inserts every record at a single time and so
+// needs neither this `Vec` nor a sort.
+fn generate_synthetic_trades() -> Vec<Trade> {
     const NUM_MARKETS: usize = 700;
     const NUM_TRADES: usize = 20_000_000;
     const START_TIME: i64 = 1717192800; // 2024-06-01 00:00:00 UTC
@@ -55,6 +82,8 @@ fn generate_synthetic_trades(input: &mut InputSession<i64, Trade, isize>) -> ()
     // Create price distribution
     let price_dist = Uniform::new(1.0, 100000.0);
 
+    let mut trades = Vec::with_capacity(NUM_TRADES);
+
     // Generate trades for each market
     for (market_idx, &num_trades) in trades_per_market.iter().enumerate() {
         let market = market_idx as u32;
@@ -66,62 +95,141 @@ fn generate_synthetic_trades(input: &mut InputSession<i64, Trade, isize>) -> ()
             // Generate price and volume
             let price = Decimal::from_f64_retain(price_dist.sample(&mut rng)).unwrap();
 
-            let trade = Trade {
+            trades.push(Trade {
                 timestamp,
                 market,
                 price,
-            };
+            });
+        }
+    }
 
-            input.insert(trade);
+    trades
+}
+
+// Drives `input` with each trade's own hour-rounded timestamp as its
+// differential time, advancing the watermark and flushing every time it
+// crosses an hour boundary. This lets downstream candles finalize and emit
+// incrementally as the dataflow runs, rather than arriving as one dump at
+// the end. `trades` is sorted here in one shot rather than arriving
+// pre-ordered, so the synthetic-generator path sorts the whole in-memory run
+// at once (see `generate_synthetic_trades`); a dump read by
+// `read_trades_into` does not go through this function.
+fn drive_trades_event_time(input: &mut InputSession<i64, Trade, isize>, mut trades: Vec<Trade>) -> Option<i64> {
+    trades.sort_by_key(|trade| trade.timestamp);
+
+    let mut watermark = None;
+    for trade in trades {
+        let hour = round_down_to(trade.timestamp, 3600);
+        if watermark != Some(hour) {
+            if watermark.is_some() {
+                input.flush();
+            }
+            input.advance_to(hour);
+            watermark = Some(hour);
         }
+        input.insert(trade);
     }
+    input.flush();
+    watermark
+}
 
-    input.advance_to(1);
+// Retracts `old_trade` and inserts `corrected` at `at`, so consumers see the
+// candles it fed recompute (a retraction followed by a re-addition) instead
+// of silently swapping in the new value.
+fn correct_trade(input: &mut InputSession<i64, Trade, isize>, at: i64, old_trade: Trade, corrected: Trade) {
+    input.advance_to(at);
+    input.remove(old_trade);
+    input.insert(corrected);
     input.flush();
 }
 
-fn main() -> () {
+fn main() {
     timely::execute_from_args(std::env::args(), move |worker| {
         let index = worker.index();
         let mut input = InputSession::new();
 
         worker.dataflow(|scope| {
             let trades = input.to_collection(scope);
-            let hourly_data = compute_hourly_data(&trades);
+
+            // Build the finest candles with the bounded aggregator, so the
+            // arrangement backing this pipeline holds one `OhlcvAgg` per
+            // (minute, market) key rather than every trade that fell into
+            // it (20M trades vs. roughly 9M keys under the generator's
+            // market skew), then derive
+            // every coarser resolution by rolling up the one below it.
+            let minute_data = compute_candles_bounded(&trades, Resolution::R1m);
+            let five_minute_data = roll_up_candles(&minute_data, Resolution::R5m);
+            let fifteen_minute_data = roll_up_candles(&five_minute_data, Resolution::R15m);
+            let hourly_data = roll_up_candles(&fifteen_minute_data, Resolution::R1h);
+            let daily_data = roll_up_candles(&hourly_data, Resolution::R1d);
+
             hourly_data.inspect(move |x| println!("HOURLY: {:?}", x));
+            daily_data.inspect(move |x| println!("DAILY: {:?}", x));
+
+            // Volume bars: one candle per 1000 base-units traded, instead of
+            // a fixed time window.
+            let volume_bars = compute_bars(
+                &trades,
+                BarKind::Volume {
+                    threshold: 1_000,
+                    by: By::Base,
+                },
+            );
+            volume_bars.inspect(move |x| println!("VOLUME BAR: {:?}", x));
+
+            // Same volume bars, but measured in quote-asset (price * size)
+            // terms instead of base-asset size.
+            let quote_volume_bars = compute_bars(
+                &trades,
+                BarKind::Volume {
+                    threshold: 1_000,
+                    by: By::Quote,
+                },
+            );
+            quote_volume_bars.inspect(move |x| println!("QUOTE VOLUME BAR: {:?}", x));
+
+            // Tick bars: one candle per 100 trades, regardless of size.
+            let tick_bars = compute_bars(&trades, BarKind::Tick { threshold: 100 });
+            tick_bars.inspect(move |x| println!("TICK BAR: {:?}", x));
         });
 
         if index == 0 {
-            generate_synthetic_trades(&mut input);
+            // A path to a fixed-width trade dump (see `encoding`) drives the
+            // dataflow off real captured trades; otherwise fall back to the
+            // synthetic generator, driven incrementally by event time.
+            match std::env::args().nth(1) {
+                Some(path) => {
+                    read_trades_into(&mut input, &path).expect("failed to read trade dump")
+                }
+                None => {
+                    let trades = generate_synthetic_trades();
+
+                    // An optional second argument captures this run's
+                    // synthetic trades to a fixed-width dump, so it can be
+                    // replayed later via the first-argument path above
+                    // instead of regenerated from scratch.
+                    if let Some(dump_path) = std::env::args().nth(2) {
+                        write_trades_to(&trades, &dump_path).expect("failed to write trade dump");
+                    }
+
+                    let late_correction = trades.first().cloned();
+
+                    if let Some(last_watermark) = drive_trades_event_time(&mut input, trades) {
+                        // Demonstrate a correction arriving after every
+                        // hour's candles have already been emitted.
+                        if let Some(old_trade) = late_correction {
+                            let corrected = Trade {
+                                price: old_trade.price + Decimal::ONE,
+                                ..old_trade.clone()
+                            };
+                            correct_trade(&mut input, last_watermark + 3600, old_trade, corrected);
+                        }
+                    }
+                }
+            }
         }
 
         Ok::<(), ()>(())
     })
     .expect("Computation failed");
 }
-
-fn compute_hourly_data<G: Scope<Timestamp = i64>>(
-    trades: &Collection<G, Trade, isize>,
-) -> Collection<G, FHLLVData, isize> {
-    // Group trades by market and hour
-    trades
-        .map(|trade| {
-            let hour_ts = round_down_to_hour(trade.timestamp);
-            ((hour_ts, trade.market.clone()), trade.price)
-        })
-        .reduce(|(hour_ts, market), input, output| {
-            let mut high_price = Decimal::MIN;
-
-            for (price, _count) in input.iter() {
-                high_price = high_price.max(**price);
-            }
-
-            let ohlcv = FHLLVData {
-                timestamp: *hour_ts,
-                market: *market,
-                high: high_price,
-            };
-            output.push((ohlcv, 1));
-        })
-        .map(|(_, ohlcv)| ohlcv)
-}
@@ -0,0 +1,316 @@
+use std::ops::{AddAssign, Mul, Neg};
+
+use differential_dataflow::difference::{Monoid, Semigroup};
+use differential_dataflow::operators::*;
+use differential_dataflow::Collection;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::resolution::{round_down_to, Resolution};
+use crate::welford::WelfordVwap;
+use crate::{decimal_from_f64, FHLLVData, Trade};
+
+// Incremental OHLCV aggregate used as a differential diff type. Unlike
+// `reduce`, which hands the reducer every trade for a key at once, this is
+// combined pairwise via `AddAssign` as trades arrive, so the arrangement
+// only ever keeps one aggregate per (bucket, market) in memory instead of
+// every trade that fell into it. `mean`/`m2`/`sum_price_size`/`sum_size` are
+// a `WelfordVwap` run, stored field-by-field (via `OrderedF64`, see below)
+// so the struct stays `Ord`-safe; `stats`/`add_assign` round-trip through
+// the shared accumulator to do the actual merge math. `Abelian` comes for
+// free from the blanket impl once `Monoid` and `Neg` hold.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct OhlcvAgg {
+    open_ts: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close_ts: i64,
+    close: Decimal,
+    volume: i64,
+    mean: OrderedF64,
+    m2: OrderedF64,
+    sum_price_size: OrderedF64,
+    sum_size: OrderedF64,
+}
+
+impl OhlcvAgg {
+    // The aggregate contributed by a single trade.
+    fn singleton(timestamp: i64, price: Decimal) -> Self {
+        let price_f = price.to_f64().unwrap_or(0.0);
+        let mut stats = WelfordVwap::new();
+        stats.push(price_f);
+
+        OhlcvAgg {
+            open_ts: timestamp,
+            open: price,
+            high: price,
+            low: price,
+            close_ts: timestamp,
+            close: price,
+            volume: 1,
+            mean: OrderedF64(stats.mean),
+            m2: OrderedF64(stats.m2),
+            sum_price_size: OrderedF64(stats.sum_price_size),
+            sum_size: OrderedF64(stats.sum_size),
+        }
+    }
+
+    fn stats(&self) -> WelfordVwap {
+        WelfordVwap {
+            n: self.volume,
+            mean: self.mean.0,
+            m2: self.m2.0,
+            sum_price_size: self.sum_price_size.0,
+            sum_size: self.sum_size.0,
+        }
+    }
+
+    pub(crate) fn into_candle(self, bucket_ts: i64, market: u32) -> FHLLVData {
+        let stats = self.stats();
+
+        FHLLVData {
+            timestamp: bucket_ts,
+            market,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: self.volume,
+            mean: decimal_from_f64(stats.mean),
+            vwap: decimal_from_f64(stats.vwap()),
+            std_dev: decimal_from_f64(stats.std_dev()),
+        }
+    }
+}
+
+impl Default for OhlcvAgg {
+    fn default() -> Self {
+        OhlcvAgg {
+            open_ts: i64::MAX,
+            open: Decimal::ZERO,
+            high: Decimal::MIN,
+            low: Decimal::MAX,
+            close_ts: i64::MIN,
+            close: Decimal::ZERO,
+            volume: 0,
+            mean: OrderedF64(0.0),
+            m2: OrderedF64(0.0),
+            sum_price_size: OrderedF64(0.0),
+            sum_size: OrderedF64(0.0),
+        }
+    }
+}
+
+impl<'a> AddAssign<&'a OhlcvAgg> for OhlcvAgg {
+    fn add_assign(&mut self, rhs: &'a Self) {
+        // Tie-break on the full `(ts, price)` pair, not just `ts`: batches
+        // merge in whatever order differential's internals happen to
+        // combine them in, so ties on `open_ts`/`close_ts` alone (trades at
+        // the same second, common at this generator's granularity) would
+        // otherwise make `open`/`close` depend on which side is `self`.
+        if (rhs.open_ts, rhs.open) < (self.open_ts, self.open) {
+            self.open_ts = rhs.open_ts;
+            self.open = rhs.open;
+        }
+        if (rhs.close_ts, rhs.close) > (self.close_ts, self.close) {
+            self.close_ts = rhs.close_ts;
+            self.close = rhs.close;
+        }
+        self.high = self.high.max(rhs.high);
+        self.low = self.low.min(rhs.low);
+
+        let mut stats = self.stats();
+        stats.merge(&rhs.stats());
+        self.mean = OrderedF64(stats.mean);
+        self.m2 = OrderedF64(stats.m2);
+        self.sum_price_size = OrderedF64(stats.sum_price_size);
+        self.sum_size = OrderedF64(stats.sum_size);
+        self.volume += rhs.volume;
+    }
+}
+
+impl Semigroup for OhlcvAgg {
+    fn is_zero(&self) -> bool {
+        self.volume == 0
+    }
+}
+
+impl Monoid for OhlcvAgg {
+    fn zero() -> Self {
+        Self::default()
+    }
+}
+
+// Retractions negate volume and the linear VWAP sums so an inserted trade
+// and its later removal net to zero; `mean`/`m2` are left as they were on
+// the singleton being retracted; they recombine correctly through
+// `AddAssign` because the weight (`volume`) driving the merge is what's
+// negated. open_ts/open/close_ts/close/high/low are NOT restored by
+// retraction: they're only ever overwritten by a smaller/larger value, so
+// removing the trade that set one leaves it referencing a trade that no
+// longer exists in the collection at all, not an approximation of it. See
+// `compute_candles_bounded`'s doc comment for what this means for callers.
+impl Neg for OhlcvAgg {
+    type Output = Self;
+
+    fn neg(mut self) -> Self {
+        self.volume = -self.volume;
+        self.sum_price_size = OrderedF64(-self.sum_price_size.0);
+        self.sum_size = OrderedF64(-self.sum_size.0);
+        self
+    }
+}
+
+// `explode` weights each singleton by the trade's own diff (+1 on insert,
+// -1 on retraction via `input.remove`), so this only ever needs to flip the
+// sign of an aggregate, not scale it by an arbitrary multiplicity.
+impl Mul<isize> for OhlcvAgg {
+    type Output = Self;
+
+    fn mul(self, rhs: isize) -> Self {
+        if rhs < 0 {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+// `f64` has no total order (NaN), but differential's default trace keeps
+// batches sorted by value, so the diff type must be `Ord`. Trade prices are
+// finite, so a total-order wrapper is safe here without pulling in a crate
+// just for this.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct OrderedF64(f64);
+
+impl Eq for OrderedF64 {}
+
+impl PartialOrd for OrderedF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedF64 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Builds OHLCV candles at `resolution` straight from trades, without ever
+// materializing a key's trades as a group. Each trade explodes into a
+// single-trade aggregate keyed by `((bucket, market), ())`; the arrangement
+// backing `reduce` merges same-key aggregates via `AddAssign` as batches
+// combine, so memory is bounded by the number of (bucket, market) keys
+// rather than the trade count, and `reduce`'s closure only has to unwrap
+// the one already-merged `OhlcvAgg` per key into a `FHLLVData`.
+//
+// Caveat: `volume`/`mean`/`vwap`/`std_dev` recompute correctly under
+// retraction (e.g. `main::correct_trade`'s retract-then-reinsert), but
+// `open`/`high`/`low`/`close` do not. They're set by "is this more
+// extreme/earlier/later than what I have", never un-set, so retracting the
+// trade that set one leaves the candle reporting a value from a trade that
+// is no longer in the collection, rather than recomputing it from what
+// remains. Safe for the common append-only case; a correction that lands
+// on the open/high/low/close trade of its bucket will produce a stale one
+// of those four fields.
+pub(crate) fn compute_candles_bounded<G: Scope<Timestamp = i64>>(
+    trades: &Collection<G, Trade, isize>,
+    resolution: Resolution,
+) -> Collection<G, FHLLVData, isize> {
+    let bucket_secs = resolution.seconds();
+
+    trades
+        .explode(move |trade| {
+            let bucket_ts = round_down_to(trade.timestamp, bucket_secs);
+            Some((
+                ((bucket_ts, trade.market), ()),
+                OhlcvAgg::singleton(trade.timestamp, trade.price),
+            ))
+        })
+        .reduce(|&(bucket_ts, market), input, output| {
+            output.push((input[0].1.clone().into_candle(bucket_ts, market), 1));
+        })
+        .map(|(_, candle)| candle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exercises the retract-then-reinsert flow `main::correct_trade` drives:
+    // a bucket with two trades, one of which is later corrected. `volume`
+    // and the VWAP sums must net out to what a fresh singleton plus the
+    // surviving trade would give; `open`/`high`/`low`/`close` are documented
+    // as NOT doing so (see `compute_candles_bounded`'s doc comment) and this
+    // asserts that known gap rather than silently accepting a regression in
+    // either direction.
+    #[test]
+    fn retraction_corrects_volume_and_vwap_but_not_high() {
+        let first = OhlcvAgg::singleton(100, Decimal::new(9, 0)); // price 9, sets high
+        let second = OhlcvAgg::singleton(101, Decimal::new(3, 0)); // price 3
+
+        let mut bucket = OhlcvAgg::default();
+        bucket.add_assign(&first);
+        bucket.add_assign(&second);
+
+        // Retract `first`, then insert its correction (price 4). The true
+        // high of what remains (3, 4) is now 4.
+        bucket.add_assign(&-first);
+        let corrected = OhlcvAgg::singleton(100, Decimal::new(4, 0));
+        bucket.add_assign(&corrected);
+
+        // volume/vwap recompute as if `corrected` had been there all along.
+        assert_eq!(bucket.volume, 2);
+        let stats = bucket.stats();
+        assert_eq!(stats.vwap(), (4.0 + 3.0) / 2.0);
+
+        // high is stale: the retracted trade's price (9) is gone from the
+        // collection entirely, yet still reported as the bucket high, even
+        // though 4 (the true max of what remains) is the correct one.
+        assert_eq!(bucket.high, Decimal::new(9, 0));
+    }
+
+    // differential-dataflow merges per-key diffs in whatever order its
+    // internal batches happen to combine, so `AddAssign` must be
+    // commutative and associative regardless of which side is `self` and
+    // regardless of how the merges are grouped. This is exactly the
+    // property the unguarded `open_ts`-only tie-break used to violate for
+    // same-second trades, which the generator produces routinely.
+    #[test]
+    fn add_assign_is_commutative() {
+        let a = OhlcvAgg::singleton(100, Decimal::new(5, 0));
+        let b = OhlcvAgg::singleton(100, Decimal::new(7, 0));
+
+        let mut a_then_b = a.clone();
+        a_then_b.add_assign(&b);
+
+        let mut b_then_a = b.clone();
+        b_then_a.add_assign(&a);
+
+        assert_eq!(a_then_b, b_then_a);
+    }
+
+    #[test]
+    fn add_assign_is_associative() {
+        let a = OhlcvAgg::singleton(100, Decimal::new(5, 0));
+        let b = OhlcvAgg::singleton(101, Decimal::new(7, 0));
+        let c = OhlcvAgg::singleton(100, Decimal::new(3, 0));
+
+        // (a + b) + c
+        let mut ab_then_c = a.clone();
+        ab_then_c.add_assign(&b);
+        ab_then_c.add_assign(&c);
+
+        // a + (b + c)
+        let mut bc = b.clone();
+        bc.add_assign(&c);
+        let mut a_then_bc = a.clone();
+        a_then_bc.add_assign(&bc);
+
+        assert_eq!(ab_then_c, a_then_bc);
+    }
+}
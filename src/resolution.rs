@@ -0,0 +1,111 @@
+use differential_dataflow::operators::*;
+use differential_dataflow::Collection;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use timely::dataflow::Scope;
+
+use crate::welford::WelfordVwap;
+use crate::{decimal_from_f64, FHLLVData};
+
+// Candle resolutions supported by the roll-up pipeline, from finest to
+// coarsest. Every resolution is derived from the one before it, so adding a
+// new one just means adding a variant and a bucket width.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub(crate) enum Resolution {
+    R1m,
+    R5m,
+    R15m,
+    R1h,
+    R1d,
+}
+
+impl Resolution {
+    // Bucket width in seconds for this resolution.
+    pub(crate) fn seconds(&self) -> i64 {
+        match self {
+            Resolution::R1m => 60,
+            Resolution::R5m => 5 * 60,
+            Resolution::R15m => 15 * 60,
+            Resolution::R1h => 60 * 60,
+            Resolution::R1d => 24 * 60 * 60,
+        }
+    }
+}
+
+// Rounds a timestamp to the start of the bucket it falls in. Uses truncating
+// division, so this rounds down only for non-negative timestamps; a negative
+// timestamp rounds toward zero.
+pub(crate) fn round_down_to(ts: i64, resolution_secs: i64) -> i64 {
+    (ts / resolution_secs) * resolution_secs
+}
+
+// Builds candles at `resolution` by rolling up `candles`, which must already
+// be bucketed at a finer resolution than `resolution`. This reduces over
+// child candles rather than raw trades, so each step up the hierarchy costs
+// one candle per child instead of one trade per child.
+pub(crate) fn roll_up_candles<G: Scope<Timestamp = i64>>(
+    candles: &Collection<G, FHLLVData, isize>,
+    resolution: Resolution,
+) -> Collection<G, FHLLVData, isize> {
+    let bucket_secs = resolution.seconds();
+
+    candles
+        .map(move |candle| {
+            let bucket_ts = round_down_to(candle.timestamp, bucket_secs);
+            ((bucket_ts, candle.market), candle)
+        })
+        .reduce(move |(bucket_ts, market), input, output| {
+            let mut open = (i64::MAX, Decimal::ZERO);
+            let mut close = (i64::MIN, Decimal::ZERO);
+            let mut high_price = Decimal::MIN;
+            let mut low_price = Decimal::MAX;
+            let mut volume: i64 = 0;
+
+            // Merge each child candle's Welford mean/variance into the
+            // parent, weighted by how many trades went into the child.
+            // Children only carry their own derived mean/std_dev/vwap, not
+            // raw per-trade history, so each one is rebuilt into a run via
+            // `WelfordVwap::from_candle` before merging.
+            let mut stats = WelfordVwap::new();
+
+            for (val, count) in input.iter() {
+                let child = *val;
+                let reps = *count as i64;
+
+                if child.timestamp < open.0 {
+                    open = (child.timestamp, child.open);
+                }
+                if child.timestamp > close.0 {
+                    close = (child.timestamp, child.close);
+                }
+                high_price = high_price.max(child.high);
+                low_price = low_price.min(child.low);
+                volume += child.volume * reps;
+
+                let child_stats = WelfordVwap::from_candle(
+                    child.mean.to_f64().unwrap_or(0.0),
+                    child.std_dev.to_f64().unwrap_or(0.0),
+                    child.vwap.to_f64().unwrap_or(0.0),
+                    child.volume,
+                );
+                for _ in 0..reps {
+                    stats.merge(&child_stats);
+                }
+            }
+
+            let rolled = FHLLVData {
+                timestamp: *bucket_ts,
+                market: *market,
+                open: open.1,
+                high: high_price,
+                low: low_price,
+                close: close.1,
+                volume,
+                mean: decimal_from_f64(stats.mean),
+                vwap: decimal_from_f64(stats.vwap()),
+                std_dev: decimal_from_f64(stats.std_dev()),
+            };
+            output.push((rolled, 1));
+        })
+        .map(|(_, candle)| candle)
+}
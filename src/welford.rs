@@ -0,0 +1,83 @@
+// Shared Welford online mean/variance + VWAP accumulator. Every candle-style
+// aggregation in this crate (candle roll-ups, bars, the bounded
+// differential diff) needs the same two numbers, accumulated the same two
+// ways: one trade at a time (`push`) or by combining two independently
+// accumulated runs (`merge`, Chan et al.'s parallel variant). Centralizing
+// both here means a fix to the merge math only has to happen once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct WelfordVwap {
+    pub(crate) n: i64,
+    pub(crate) mean: f64,
+    pub(crate) m2: f64,
+    pub(crate) sum_price_size: f64,
+    pub(crate) sum_size: f64,
+}
+
+impl WelfordVwap {
+    pub(crate) fn new() -> Self {
+        WelfordVwap {
+            n: 0,
+            mean: 0.0,
+            m2: 0.0,
+            sum_price_size: 0.0,
+            sum_size: 0.0,
+        }
+    }
+
+    // Rebuilds a run from the scalars a finished candle actually carries
+    // (mean, std_dev, vwap, volume) rather than the raw per-trade history,
+    // for merging candles that have already been rolled up once.
+    pub(crate) fn from_candle(mean: f64, std_dev: f64, vwap: f64, volume: i64) -> Self {
+        WelfordVwap {
+            n: volume,
+            mean,
+            m2: std_dev * std_dev * volume as f64,
+            sum_price_size: vwap * volume as f64,
+            sum_size: volume as f64,
+        }
+    }
+
+    // Welford's online update for a single trade of size 1 (until trades
+    // carry their own size).
+    pub(crate) fn push(&mut self, price: f64) {
+        self.n += 1;
+        let delta = price - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (price - self.mean);
+        self.sum_price_size += price;
+        self.sum_size += 1.0;
+    }
+
+    // Welford's parallel merge (Chan et al.), weighted by how many trades
+    // went into each run.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        let total_n = self.n + other.n;
+        if total_n == 0 {
+            self.mean = 0.0;
+            self.m2 = 0.0;
+        } else {
+            let delta = other.mean - self.mean;
+            self.mean = (self.n as f64 * self.mean + other.n as f64 * other.mean) / total_n as f64;
+            self.m2 += other.m2 + delta * delta * (self.n as f64 * other.n as f64) / total_n as f64;
+        }
+        self.sum_price_size += other.sum_price_size;
+        self.sum_size += other.sum_size;
+        self.n = total_n;
+    }
+
+    pub(crate) fn std_dev(&self) -> f64 {
+        if self.n > 0 {
+            (self.m2 / self.n as f64).sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    pub(crate) fn vwap(&self) -> f64 {
+        if self.sum_size > 0.0 {
+            self.sum_price_size / self.sum_size
+        } else {
+            0.0
+        }
+    }
+}